@@ -1,14 +1,15 @@
-use anyhow::{bail, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use chrono;
 use futures::future::join_all;
 use getopts::Options;
 use log::*;
 use logosaurus::{self, Logger, L_LEVEL, L_TIME};
-use reqwest::Client as HttpClient;
+use reqwest::{Client as HttpClient, RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use spmc;
 use spotifyimport::access_token::{self, TokenResponse, SP_DC_INSTRUCTIONS};
+use std::collections::HashSet;
 use std::env;
 use std::fmt;
 use std::fs::File;
@@ -17,8 +18,10 @@ use std::io::{BufReader, BufWriter};
 use std::process;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use tokio;
 use tokio::sync::mpsc;
+use tokio::sync::Mutex as AsyncMutex;
 
 #[tokio::main]
 async fn main() {
@@ -37,13 +40,72 @@ async fn main() {
 
 const N_WORKERS: u32 = 16;
 
+// Spotify's "save tracks" endpoint accepts at most this many IDs per request.
+const CHUNK_SIZE: usize = 50;
+
+// Spotify's "add items to playlist" endpoint accepts at most this many URIs
+// per request.
+const PLAYLIST_CHUNK_SIZE: usize = 100;
+
+// Retry tuning for rate limiting (HTTP 429) and transient server errors (5xx).
+const MAX_ATTEMPTS: u32 = 5;
+const MAX_BACKOFF_SECS: u64 = 30;
+
+// Refresh the access token once it's within this many milliseconds of expiry.
+const REFRESH_THRESHOLD_MS: i64 = 60_000;
+
+// Holds the access token currently in use plus its expiry, shared between the
+// search and add workers so that a mid-import expiry only triggers a single
+// refresh rather than one per worker.
+struct TokenState {
+    access_token: String,
+    expiry_ms: i64,
+}
+
+type SharedToken = Arc<AsyncMutex<TokenState>>;
+
+// Returns a token that's safely within its expiry window, refreshing the
+// shared token first if it's close to expiring. Concurrent callers serialize
+// on `shared`'s lock, so only the first caller past the threshold performs
+// the refresh; the rest observe the refreshed token once they acquire it.
+async fn fresh_token(
+    c: &HttpClient,
+    sp_dc: &str,
+    sp_key: &str,
+    shared: &SharedToken,
+) -> Result<String, Error> {
+    let mut state = shared.lock().await;
+
+    let now_ms = chrono::offset::Local::now().timestamp_millis();
+    if state.expiry_ms - now_ms < REFRESH_THRESHOLD_MS {
+        let TokenResponse {
+            access_token,
+            expiry_ms,
+        } = access_token::fetch(c, sp_dc, sp_key)
+            .await
+            .context("refresh access token")?;
+        info!("refreshed access token");
+        state.access_token = access_token;
+        state.expiry_ms = expiry_ms;
+    }
+
+    Ok(state.access_token.clone())
+}
+
 fn print_help() {
     eprintln!(
-        r"usage: {} [--mutate] <sp_dc> <sp_key>
+        r"usage: {} [--mutate] [--playlist <name>] [--report] <sp_dc> <sp_key>
 
 Standard input should be JSON from the
 https://scrobble.growl.space/api/v1/scrobbled API endpoint.
 
+By default, resolved tracks are added to Liked Songs. Pass --playlist to add
+them to a playlist with the given name instead, creating it if it doesn't
+already exist (requires --mutate). Pass --report to make no changes and
+instead print how the input songs compare to the existing library: Liked
+Songs by default, or the playlist named by --playlist, which must already
+exist.
+
 To obtain sp_dc and sp_key:
 {}",
         env::args().nth(0).unwrap(),
@@ -51,16 +113,57 @@ To obtain sp_dc and sp_key:
     );
 }
 
+// A resolved Spotify track: its id, used by the Liked Songs endpoints, and
+// its uri, used by the playlist endpoints.
+#[derive(Debug)]
+struct Resolved {
+    id: String,
+    uri: String,
+}
+
+#[derive(Debug)]
+enum SearchStatus {
+    Found(Song, Resolved), // song, resolved track
+    Skipped(Song, String), // song, reason
+}
+
 #[derive(Debug)]
 enum AddStatus {
     Added(Song, String),   // song, id
+    AlreadyPresent(Song),  // song already in the library; left untouched
     Skipped(Song, String), // song, reason
 }
 
+// Where resolved tracks are added.
+enum Destination {
+    LikedSongs,
+    Playlist(String), // playlist id
+}
+
+impl Destination {
+    fn chunk_size(&self) -> usize {
+        match self {
+            Destination::LikedSongs => CHUNK_SIZE,
+            Destination::Playlist(_) => PLAYLIST_CHUNK_SIZE,
+        }
+    }
+}
+
 async fn run() -> Result<(), Error> {
     // parse flags
     let mut opts = Options::new();
     opts.optflag("", "mutate", "actually make changes (add songs)");
+    opts.optopt(
+        "",
+        "playlist",
+        "add songs to this playlist instead of Liked Songs, creating it if needed",
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "report",
+        "make no changes; print how the input songs compare to the existing library",
+    );
     opts.optflag("h", "help", "print help information");
     let matches = match opts.parse(&env::args().skip(1).collect::<Vec<String>>()) {
         Ok(m) => {
@@ -81,6 +184,8 @@ async fn run() -> Result<(), Error> {
         process::exit(0);
     }
     let mutate = matches.opt_present("mutate");
+    let playlist_name = matches.opt_str("playlist");
+    let report = matches.opt_present("report");
 
     // parse arguments
     let sp_dc = matches.free[0].clone();
@@ -88,10 +193,41 @@ async fn run() -> Result<(), Error> {
 
     let http_client = HttpClient::new();
 
-    // NOTE: the expiry seems to be 1 hour, which should suffice for our purposes.
-    let TokenResponse { access_token, .. } = access_token::fetch(&http_client, &sp_dc, &sp_key)
+    // NOTE: the expiry is ~1 hour; a large import can outlive that, so the
+    // token is refreshed mid-import via `shared_token` instead of being
+    // fetched once and cloned to every worker.
+    let TokenResponse {
+        access_token,
+        expiry_ms,
+    } = access_token::fetch(&http_client, &sp_dc, &sp_key)
         .await
         .context("fetch access token")?;
+    let shared_token: SharedToken = Arc::new(AsyncMutex::new(TokenState {
+        access_token,
+        expiry_ms,
+    }));
+
+    if report {
+        return run_report(
+            &http_client,
+            &sp_dc,
+            &sp_key,
+            &shared_token,
+            playlist_name.as_deref(),
+        )
+        .await;
+    }
+
+    let destination = match playlist_name {
+        Some(name) => {
+            let token = fresh_token(&http_client, &sp_dc, &sp_key, &shared_token).await?;
+            let id = resolve_playlist(&http_client, &token, &name, mutate)
+                .await
+                .context("resolve playlist")?;
+            Destination::Playlist(id)
+        }
+        None => Destination::LikedSongs,
+    };
 
     // read scrobbled songs
     let r = BufReader::new(io::stdin());
@@ -102,7 +238,7 @@ async fn run() -> Result<(), Error> {
     let (mut tx, rx) = spmc::channel::<Song>();
     let mut handles = Vec::new();
 
-    let (added_tx, mut added_rx) = mpsc::channel::<AddStatus>(1);
+    let (resolved_tx, mut resolved_rx) = mpsc::channel::<SearchStatus>(1);
 
     // send work along channel
     handles.push(tokio::spawn(async move {
@@ -111,72 +247,138 @@ async fn run() -> Result<(), Error> {
         }
     }));
 
-    // consume work from channel
+    // consume work from channel, resolving each song to a Spotify track id
     for _ in 0..N_WORKERS {
         let rx = rx.clone();
-        let mut added_tx = added_tx.clone();
+        let mut resolved_tx = resolved_tx.clone();
         let http_client = http_client.clone();
-        let token = access_token.clone();
+        let sp_dc = sp_dc.clone();
+        let sp_key = sp_key.clone();
+        let shared_token = Arc::clone(&shared_token);
 
         handles.push(tokio::spawn(async move {
             loop {
                 match rx.recv() {
-                    Ok(song) => match search_spotify_track(&http_client, &token, &song).await {
-                        Ok(id) => {
-                            if mutate {
-                                // TODO: Consider checking if song already
-                                // exists in Spotify. Adding an existing song
-                                // works, but updates the "date added" field,
-                                // which might be undesirable.
-                                if let Err(e) =
-                                    add_spotify_liked_track(&http_client, &token, &id).await
-                                {
-                                    added_tx
-                                        .send(AddStatus::Skipped(
+                    Ok(song) => {
+                        let token =
+                            match fresh_token(&http_client, &sp_dc, &sp_key, &shared_token).await {
+                                Ok(token) => token,
+                                Err(e) => {
+                                    resolved_tx
+                                        .send(SearchStatus::Skipped(
                                             song,
-                                            format!("{}: {}", "add track", e),
+                                            format!("{}: {}", "refresh token", e),
                                         ))
                                         .await
                                         .unwrap();
                                     continue;
                                 }
+                            };
+
+                        match search_spotify_track(&http_client, &token, &song).await {
+                            Ok(resolved) => {
+                                resolved_tx
+                                    .send(SearchStatus::Found(song, resolved))
+                                    .await
+                                    .unwrap();
+                            }
+                            Err(e) => {
+                                resolved_tx
+                                    .send(SearchStatus::Skipped(
+                                        song,
+                                        format!("{}: {}", "search track", e),
+                                    ))
+                                    .await
+                                    .unwrap();
                             }
-                            added_tx
-                                .send(AddStatus::Added(song, String::from(id)))
-                                .await
-                                .unwrap();
-                        }
-                        Err(e) => {
-                            added_tx
-                                .send(AddStatus::Skipped(
-                                    song,
-                                    format!("{}: {}", "search track", e),
-                                ))
-                                .await
-                                .unwrap();
                         }
-                    },
+                    }
                     Err(_) => break,
                 }
             }
         }));
     }
 
+    drop(resolved_tx);
+
+    let (added_tx, mut added_rx) = mpsc::channel::<AddStatus>(1);
+
+    // batch resolved tracks into chunks sized for `destination` and add them,
+    // keeping track of which resolved track came from which Song so that a
+    // failed batch can still report exactly which songs were skipped.
+    handles.push(tokio::spawn(async move {
+        let http_client = http_client;
+        let chunk_size = destination.chunk_size();
+        let mut batch: Vec<(Resolved, Song)> = Vec::new();
+
+        while let Some(status) = resolved_rx.recv().await {
+            match status {
+                SearchStatus::Found(song, resolved) => {
+                    if !mutate {
+                        added_tx
+                            .send(AddStatus::Added(song, resolved.id))
+                            .await
+                            .unwrap();
+                        continue;
+                    }
+
+                    batch.push((resolved, song));
+                    if batch.len() == chunk_size {
+                        flush_batch(
+                            &http_client,
+                            &sp_dc,
+                            &sp_key,
+                            &shared_token,
+                            &destination,
+                            &mut batch,
+                            &mut added_tx,
+                        )
+                        .await;
+                    }
+                }
+                SearchStatus::Skipped(song, reason) => {
+                    added_tx
+                        .send(AddStatus::Skipped(song, reason))
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            flush_batch(
+                &http_client,
+                &sp_dc,
+                &sp_key,
+                &shared_token,
+                &destination,
+                &mut batch,
+                &mut added_tx,
+            )
+            .await;
+        }
+    }));
+
     drop(added_tx);
 
     // collect added/failure info
     let total = s.total;
-    let failed_songs: Arc<Mutex<Vec<Song>>> = Arc::new(Mutex::new(Vec::new()));
+    let summary: Arc<Mutex<Summary>> = Arc::new(Mutex::new(Summary::default()));
 
-    let failed_songs_clone = Arc::clone(&failed_songs);
+    let summary_clone = Arc::clone(&summary);
     handles.push(tokio::spawn(async move {
         loop {
             match added_rx.recv().await {
                 Some(AddStatus::Added(song, id)) => {
+                    summary_clone.lock().unwrap().added += 1;
                     info!("added {} {}", song, id);
                 }
+                Some(AddStatus::AlreadyPresent(song)) => {
+                    summary_clone.lock().unwrap().already_present += 1;
+                    info!("already in library: {}", song);
+                }
                 Some(AddStatus::Skipped(song, reason)) => {
-                    failed_songs_clone.lock().unwrap().push(song.clone());
+                    summary_clone.lock().unwrap().failed.push(song.clone());
                     error!("{}; skipped {}", reason, song);
                 }
                 None => {
@@ -188,69 +390,584 @@ async fn run() -> Result<(), Error> {
 
     join_all(handles).await;
 
-    let added = total as usize - failed_songs.lock().unwrap().len();
+    let summary = Arc::try_unwrap(summary).unwrap().into_inner().unwrap();
 
-    if !failed_songs.lock().unwrap().is_empty() {
+    if !summary.failed.is_empty() {
         let failure_filename =
             format!("failures_{}.json", chrono::offset::Local::now().timestamp(),);
 
         info!(
-            "total songs: {}, added: {}, skipped songs written to: {}",
-            total, added, failure_filename,
+            "total songs: {}, added: {}, already in library: {}, skipped songs written to: {}",
+            total, summary.added, summary.already_present, failure_filename,
         );
         let f = File::create(failure_filename).context("create output file")?;
-        let failed_vec = Arc::try_unwrap(failed_songs).unwrap().into_inner().unwrap();
-        serde_json::to_writer_pretty(BufWriter::new(f), &failed_vec)
+        serde_json::to_writer_pretty(BufWriter::new(f), &summary.failed)
             .context("write failed songs")?;
     } else {
-        info!("total songs: {}, added: {}", total, added);
+        info!(
+            "total songs: {}, added: {}, already in library: {}",
+            total, summary.added, summary.already_present,
+        );
     }
 
     Ok(())
 }
 
-async fn search_spotify_track(c: &HttpClient, token: &str, song: &Song) -> Result<String, Error> {
-    let url = "https://api.spotify.com/v1/search";
-    let q = search_query(&song.title, &song.artist_name, &song.album_title);
+#[derive(Debug, Default)]
+struct Summary {
+    added: usize,
+    already_present: usize,
+    failed: Vec<Song>,
+}
+
+#[derive(Debug)]
+enum ReportStatus {
+    AlreadyPresent(Song),
+    ResolvableMissing(Song),
+    Unresolvable(Song),
+}
+
+// Classifies each input song against the existing library, without making
+// any changes: already present, resolvable on Spotify but missing from the
+// library, or unresolvable (the search returned zero tracks or failed, or
+// the token could not be refreshed). The library is
+// Liked Songs by default, or the named playlist if `playlist_name` is
+// given (the playlist must already exist; --report never creates one).
+// Prints summary counts and a JSON breakdown of the three groups.
+async fn run_report(
+    http_client: &HttpClient,
+    sp_dc: &str,
+    sp_key: &str,
+    shared_token: &SharedToken,
+    playlist_name: Option<&str>,
+) -> Result<(), Error> {
+    let r = BufReader::new(io::stdin());
+    let s: Scrobbled = serde_json::from_reader(r).context("json deserialize")?;
+    let total = s.total;
+    let songs = s.songs;
+
+    let token = fresh_token(http_client, sp_dc, sp_key, shared_token).await?;
+    let existing = match playlist_name {
+        Some(name) => {
+            let playlist_id = find_playlist(http_client, &token, name)
+                .await
+                .context("look up playlist")?
+                .ok_or_else(|| anyhow!("playlist {:?} not found", name))?;
+            fetch_playlist_track_ids(http_client, &token, &playlist_id)
+                .await
+                .context("fetch existing playlist tracks")?
+        }
+        None => fetch_liked_track_ids(http_client, &token)
+            .await
+            .context("fetch existing liked songs")?,
+    };
+    let existing = Arc::new(existing);
+
+    let (mut tx, rx) = spmc::channel::<Song>();
+    let mut handles = Vec::new();
+    let (status_tx, mut status_rx) = mpsc::channel::<ReportStatus>(1);
+
+    handles.push(tokio::spawn(async move {
+        for song in songs {
+            tx.send(song).unwrap();
+        }
+    }));
+
+    for _ in 0..N_WORKERS {
+        let rx = rx.clone();
+        let mut status_tx = status_tx.clone();
+        let http_client = http_client.clone();
+        let sp_dc = sp_dc.to_string();
+        let sp_key = sp_key.to_string();
+        let shared_token = Arc::clone(shared_token);
+        let existing = Arc::clone(&existing);
+
+        handles.push(tokio::spawn(async move {
+            loop {
+                match rx.recv() {
+                    Ok(song) => {
+                        let token =
+                            match fresh_token(&http_client, &sp_dc, &sp_key, &shared_token).await {
+                                Ok(token) => token,
+                                Err(e) => {
+                                    warn!("refresh token: {}; marking {} unresolvable", e, song);
+                                    status_tx
+                                        .send(ReportStatus::Unresolvable(song))
+                                        .await
+                                        .unwrap();
+                                    continue;
+                                }
+                            };
+
+                        let status = match search_spotify_track(&http_client, &token, &song).await {
+                            Ok(resolved) if existing.contains(&resolved.id) => {
+                                ReportStatus::AlreadyPresent(song)
+                            }
+                            Ok(_) => ReportStatus::ResolvableMissing(song),
+                            Err(e) => {
+                                warn!("search track: {}; marking {} unresolvable", e, song);
+                                ReportStatus::Unresolvable(song)
+                            }
+                        };
+                        status_tx.send(status).await.unwrap();
+                    }
+                    Err(_) => break,
+                }
+            }
+        }));
+    }
+
+    drop(status_tx);
+
+    let report: Arc<Mutex<Report>> = Arc::new(Mutex::new(Report::default()));
+    let report_clone = Arc::clone(&report);
+    handles.push(tokio::spawn(async move {
+        while let Some(status) = status_rx.recv().await {
+            let mut report = report_clone.lock().unwrap();
+            match status {
+                ReportStatus::AlreadyPresent(song) => report.already_present.push(song),
+                ReportStatus::ResolvableMissing(song) => report.resolvable_missing.push(song),
+                ReportStatus::Unresolvable(song) => report.unresolvable.push(song),
+            }
+        }
+    }));
+
+    join_all(handles).await;
+
+    let report = Arc::try_unwrap(report).unwrap().into_inner().unwrap();
+
+    info!(
+        "total songs: {}, already in library: {}, resolvable but missing: {}, unresolvable: {}",
+        total,
+        report.already_present.len(),
+        report.resolvable_missing.len(),
+        report.unresolvable.len(),
+    );
+
+    serde_json::to_writer_pretty(io::stdout(), &report).context("write report")?;
+    println!();
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    already_present: Vec<Song>,
+    resolvable_missing: Vec<Song>,
+    unresolvable: Vec<Song>,
+}
+
+// Pages through the user's Liked Songs (GET /v1/me/tracks, limit 50) and
+// returns the set of track ids currently in the library.
+async fn fetch_liked_track_ids(c: &HttpClient, token: &str) -> Result<HashSet<String>, Error> {
+    let mut ids = HashSet::new();
+    let mut url = String::from("https://api.spotify.com/v1/me/tracks?limit=50");
 
-    let rsp = c
-        .get(url)
-        .header("authorization", format!("Bearer {}", token))
-        .query(&[("q", &q[..]), ("type", "track"), ("limit", "1")])
-        .send()
+    loop {
+        let page: SavedTracksPage = send_with_retry(|| {
+            c.get(&url)
+                .header("authorization", format!("Bearer {}", token))
+        })
+        .await?
+        .json()
         .await
-        .context("build and execute request")?;
+        .context("json deserialize")?;
 
-    if rsp.status() != 200 {
-        bail!("bad status code: {}", rsp.status());
+        for item in page.items {
+            ids.insert(item.track.id);
+        }
+
+        match page.next {
+            Some(next) => url = next,
+            None => break,
+        }
     }
 
+    Ok(ids)
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedTracksPage {
+    items: Vec<SavedTrackItem>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SavedTrackItem {
+    track: Item,
+}
+
+// Pages through a playlist's tracks (GET /v1/playlists/{id}/tracks, limit
+// 100) and returns the set of track ids it currently contains.
+async fn fetch_playlist_track_ids(
+    c: &HttpClient,
+    token: &str,
+    playlist_id: &str,
+) -> Result<HashSet<String>, Error> {
+    let mut ids = HashSet::new();
+    let mut url = format!(
+        "https://api.spotify.com/v1/playlists/{}/tracks?limit=100",
+        playlist_id
+    );
+
+    loop {
+        let page: PlaylistTracksPage = send_with_retry(|| {
+            c.get(&url)
+                .header("authorization", format!("Bearer {}", token))
+        })
+        .await?
+        .json()
+        .await
+        .context("json deserialize")?;
+
+        for item in page.items {
+            ids.insert(item.track.id);
+        }
+
+        match page.next {
+            Some(next) => url = next,
+            None => break,
+        }
+    }
+
+    Ok(ids)
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistTracksPage {
+    items: Vec<PlaylistTrackItem>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistTrackItem {
+    track: Item,
+}
+
+async fn search_spotify_track(c: &HttpClient, token: &str, song: &Song) -> Result<Resolved, Error> {
+    let url = "https://api.spotify.com/v1/search";
+    let q = search_query(&song.title, &song.artist_name, &song.album_title);
+
+    let rsp = send_with_retry(|| {
+        c.get(url)
+            .header("authorization", format!("Bearer {}", token))
+            .query(&[("q", &q[..]), ("type", "track"), ("limit", "1")])
+    })
+    .await?;
+
     let rsp: SearchResponse = rsp.json().await.context("json deserialize")?;
     if rsp.tracks.items.is_empty() {
         bail!("found zero tracks");
     }
 
-    Ok(rsp.tracks.items[0].id.clone())
+    let item = &rsp.tracks.items[0];
+    Ok(Resolved {
+        id: item.id.clone(),
+        uri: item.uri.clone(),
+    })
 }
 
-async fn add_spotify_liked_track(c: &HttpClient, token: &str, id: &str) -> Result<(), Error> {
+async fn add_spotify_liked_tracks(
+    c: &HttpClient,
+    token: &str,
+    ids: &[String],
+) -> Result<(), Error> {
     let url = "https://api.spotify.com/v1/me/tracks";
-    let rsp = c
-        .put(url)
-        .header("authorization", format!("Bearer {}", token))
-        .header("content-length", "0")
-        .query(&[("ids", id)])
-        .send()
+    let ids = ids.join(",");
+
+    send_with_retry(|| {
+        c.put(url)
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-length", "0")
+            .query(&[("ids", &ids[..])])
+    })
+    .await?;
+
+    Ok(())
+}
+
+// Looks up the id of the first playlist named `name` among the current
+// user's playlists (GET /v1/me/playlists), without creating one. The
+// playlist's owner is not checked.
+async fn find_playlist(c: &HttpClient, token: &str, name: &str) -> Result<Option<String>, Error> {
+    let mut url = String::from("https://api.spotify.com/v1/me/playlists?limit=50");
+    loop {
+        let page: PlaylistsPage = send_with_retry(|| {
+            c.get(&url)
+                .header("authorization", format!("Bearer {}", token))
+        })
+        .await?
+        .json()
         .await
-        .context("build and execute request")?;
+        .context("json deserialize")?;
+
+        if let Some(playlist) = page.items.into_iter().find(|p| p.name == name) {
+            return Ok(Some(playlist.id));
+        }
 
-    if rsp.status() != 200 {
-        bail!("bad status code: {}", rsp.status());
+        match page.next {
+            Some(next) => url = next,
+            None => break,
+        }
+    }
+
+    Ok(None)
+}
+
+// Finds the id of the playlist named `name` via `find_playlist`, creating it (as a private playlist) if none exists yet. Creation only
+// happens when `mutate` is set; without it, a placeholder id is returned
+// instead, since the caller never uses it to actually add tracks in that
+// case. This keeps --playlist without --mutate a true no-op, matching
+// every other write in this program.
+async fn resolve_playlist(
+    c: &HttpClient,
+    token: &str,
+    name: &str,
+    mutate: bool,
+) -> Result<String, Error> {
+    if let Some(id) = find_playlist(c, token, name).await? {
+        return Ok(id);
+    }
+
+    if !mutate {
+        return Ok(format!("(preview only; would create playlist {:?})", name));
     }
 
+    let me: Me = send_with_retry(|| {
+        c.get("https://api.spotify.com/v1/me")
+            .header("authorization", format!("Bearer {}", token))
+    })
+    .await?
+    .json()
+    .await
+    .context("json deserialize")?;
+
+    let create_url = format!("https://api.spotify.com/v1/users/{}/playlists", me.id);
+    let body = CreatePlaylistRequest {
+        name,
+        public: false,
+    };
+
+    let playlist: Playlist = send_with_retry(|| {
+        c.post(&create_url)
+            .header("authorization", format!("Bearer {}", token))
+            .json(&body)
+    })
+    .await?
+    .json()
+    .await
+    .context("json deserialize")?;
+
+    Ok(playlist.id)
+}
+
+async fn add_to_playlist(
+    c: &HttpClient,
+    token: &str,
+    playlist_id: &str,
+    uris: &[String],
+) -> Result<(), Error> {
+    let url = format!(
+        "https://api.spotify.com/v1/playlists/{}/tracks",
+        playlist_id
+    );
+    let body = AddPlaylistItemsRequest { uris };
+
+    send_with_retry(|| {
+        c.post(&url)
+            .header("authorization", format!("Bearer {}", token))
+            .json(&body)
+    })
+    .await?;
+
     Ok(())
 }
 
+// Sends the request built by `build` (called fresh on every attempt, since a
+// `RequestBuilder` can't be reused once sent), retrying on rate limiting and
+// transient server errors: a 429 is retried after the `Retry-After` duration
+// it reports, and a 5xx is retried with exponential backoff (1s, 2s, 4s, ...,
+// capped at `MAX_BACKOFF_SECS`). Gives up after `MAX_ATTEMPTS` attempts.
+async fn send_with_retry<F>(build: F) -> Result<Response, Error>
+where
+    F: Fn() -> RequestBuilder,
+{
+    for attempt in 0..MAX_ATTEMPTS {
+        let rsp = build().send().await.context("build and execute request")?;
+
+        match rsp.status() {
+            s if s.is_success() => return Ok(rsp),
+            s if s == 429 => {
+                let wait = retry_after(&rsp).unwrap_or(Duration::from_secs(1));
+                warn!(
+                    "rate limited (attempt {}/{}); retrying in {:?}",
+                    attempt + 1,
+                    MAX_ATTEMPTS,
+                    wait
+                );
+                tokio::time::sleep(wait).await;
+            }
+            s if s.is_server_error() => {
+                let wait = backoff(attempt);
+                warn!(
+                    "bad status code: {} (attempt {}/{}); retrying in {:?}",
+                    s,
+                    attempt + 1,
+                    MAX_ATTEMPTS,
+                    wait
+                );
+                tokio::time::sleep(wait).await;
+            }
+            s => bail!("bad status code: {}", s),
+        }
+    }
+
+    bail!("bad status code: gave up after {} attempts", MAX_ATTEMPTS);
+}
+
+fn retry_after(rsp: &Response) -> Option<Duration> {
+    rsp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let secs = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+    Duration::from_secs(secs.min(MAX_BACKOFF_SECS))
+}
+
+async fn check_already_liked(
+    c: &HttpClient,
+    token: &str,
+    ids: &[String],
+) -> Result<Vec<bool>, Error> {
+    let url = "https://api.spotify.com/v1/me/tracks/contains";
+    let ids = ids.join(",");
+
+    let rsp = send_with_retry(|| {
+        c.get(url)
+            .header("authorization", format!("Bearer {}", token))
+            .query(&[("ids", &ids[..])])
+    })
+    .await?;
+
+    rsp.json::<Vec<bool>>().await.context("json deserialize")
+}
+
+// Adds a batch of (Resolved, Song) pairs to `destination` in a single
+// request, reporting an AddStatus for every song in the batch. When adding
+// to Liked Songs, tracks already in the library are reported as
+// AddStatus::AlreadyPresent instead (leaving their "date added" untouched);
+// playlists have no such check, since duplicate tracks are permitted there.
+// Clears the batch.
+async fn flush_batch(
+    c: &HttpClient,
+    sp_dc: &str,
+    sp_key: &str,
+    shared_token: &SharedToken,
+    destination: &Destination,
+    batch: &mut Vec<(Resolved, Song)>,
+    added_tx: &mut mpsc::Sender<AddStatus>,
+) {
+    let token = match fresh_token(c, sp_dc, sp_key, shared_token).await {
+        Ok(token) => token,
+        Err(e) => {
+            let reason = format!("{}: {}", "refresh token", e);
+            for (_, song) in batch.drain(..) {
+                added_tx
+                    .send(AddStatus::Skipped(song, reason.clone()))
+                    .await
+                    .unwrap();
+            }
+            return;
+        }
+    };
+
+    match destination {
+        Destination::LikedSongs => {
+            let ids: Vec<String> = batch.iter().map(|(r, _)| r.id.clone()).collect();
+
+            let already_liked = match check_already_liked(c, &token, &ids).await {
+                Ok(already_liked) => already_liked,
+                Err(e) => {
+                    let reason = format!("{}: {}", "check library membership", e);
+                    for (_, song) in batch.drain(..) {
+                        added_tx
+                            .send(AddStatus::Skipped(song, reason.clone()))
+                            .await
+                            .unwrap();
+                    }
+                    return;
+                }
+            };
+
+            let mut to_add: Vec<(Resolved, Song)> = Vec::new();
+            for ((resolved, song), present) in batch.drain(..).zip(already_liked) {
+                if present {
+                    added_tx
+                        .send(AddStatus::AlreadyPresent(song))
+                        .await
+                        .unwrap();
+                } else {
+                    to_add.push((resolved, song));
+                }
+            }
+
+            if to_add.is_empty() {
+                return;
+            }
+
+            let ids: Vec<String> = to_add.iter().map(|(r, _)| r.id.clone()).collect();
+            match add_spotify_liked_tracks(c, &token, &ids).await {
+                Ok(()) => {
+                    for (resolved, song) in to_add {
+                        added_tx
+                            .send(AddStatus::Added(song, resolved.id))
+                            .await
+                            .unwrap();
+                    }
+                }
+                Err(e) => {
+                    let reason = format!("{}: {}", "add tracks", e);
+                    for (_, song) in to_add {
+                        added_tx
+                            .send(AddStatus::Skipped(song, reason.clone()))
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        }
+        Destination::Playlist(playlist_id) => {
+            let uris: Vec<String> = batch.iter().map(|(r, _)| r.uri.clone()).collect();
+
+            match add_to_playlist(c, &token, playlist_id, &uris).await {
+                Ok(()) => {
+                    for (resolved, song) in batch.drain(..) {
+                        added_tx
+                            .send(AddStatus::Added(song, resolved.id))
+                            .await
+                            .unwrap();
+                    }
+                }
+                Err(e) => {
+                    let reason = format!("{}: {}", "add to playlist", e);
+                    for (_, song) in batch.drain(..) {
+                        added_tx
+                            .send(AddStatus::Skipped(song, reason.clone()))
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
 // Apple Music uses these suffixes, but Spotify doesn't.
 const ALBUM_TRIM_SUFFIXES: &[&str] = &[
     " - EP",
@@ -305,6 +1022,34 @@ struct Item {
 
 type SpotifyUri = String;
 
+#[derive(Debug, Deserialize)]
+struct Me {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistsPage {
+    items: Vec<Playlist>,
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Playlist {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatePlaylistRequest<'a> {
+    name: &'a str,
+    public: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AddPlaylistItemsRequest<'a> {
+    uris: &'a [String],
+}
+
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 struct Scrobbled {